@@ -1,10 +1,16 @@
 #![no_std]
 #![no_main]
 
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+
 use defmt::info;
 use defmt_rtt as _;
 use embassy_executor::Spawner;
-use embassy_futures::join::join;
+use embassy_futures::{
+    join::join,
+    select::{select, select3, Either, Either3},
+};
 use embassy_rp::{
     bind_interrupts,
     gpio::{Level::Low, Output},
@@ -13,16 +19,19 @@ use embassy_rp::{
     usb::{Driver, InterruptHandler},
 };
 use embassy_sync::{
-    blocking_mutex::raw::CriticalSectionRawMutex,
-    watch::{Sender, Watch},
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex as BlockingMutex},
+    channel::Channel,
+    signal::Signal,
+    watch::Watch,
 };
-use embassy_time::Timer;
+use embassy_time::{Duration, Instant, Timer};
 use embassy_usb::{
     class::hid::{Config as HIDConfig, HidReaderWriter, ReportId, RequestHandler, State},
     control::OutResponse,
     Builder, Config, Handler,
 };
 use panic_probe as _;
+use rp2040_flash::flash::{flash_range_erase, flash_range_program};
 
 // -----
 
@@ -32,30 +41,288 @@ bind_interrupts!(struct Irqs {
 
 const MAX_DURATION: u32 = 1000 * 60 * 10; // 10 Minutes
 
+/// How many independently switchable channels this board drives.
+const NUM_CHANNELS: usize = 4;
+
+/// Report ID of the OUT command report.
+const COMMAND_REPORT_ID: u8 = 1;
+/// First IN status report ID; channel `c` uses `STATUS_REPORT_BASE + c`.
+const STATUS_REPORT_BASE: u8 = 2;
+/// Feature report ID carrying the persisted settings (after the status reports).
+const SETTINGS_REPORT_ID: u8 = STATUS_REPORT_BASE + NUM_CHANNELS as u8;
+
+/// Depth of the status-notification queue: enough for an on+off transition on
+/// every channel to be buffered before the writer task drains them.
+const STATUS_QUEUE_LEN: usize = NUM_CHANNELS * 2;
+
+/// High bit of a command's duration field: restart/extend an active pulse
+/// instead of discarding the new value.
+const RETRIGGER_FLAG: u32 = 1 << 31;
+/// Second-highest bit: wake a suspended host and wait for it to resume before
+/// driving the relay, so the pulse never fires on a still-suspended bus.
+const WAKE_FLAG: u32 = 1 << 30;
+
+/// Set while the host has suspended the bus, so a queued command knows it must
+/// wake the host (remote wakeup) before acting on the relay.
+static SUSPENDED: AtomicBool = AtomicBool::new(false);
+
+/// Per-channel command channel: the host writes a command and the matching
+/// toggle task acts on it. One receiver each (the channel's own task).
+static COMMANDS: [Watch<CriticalSectionRawMutex, Command, 1>; NUM_CHANNELS] =
+    [const { Watch::new() }; NUM_CHANNELS];
+
+/// Per-channel "drop now" signals, raised when the bus leaves the Configured
+/// state so no channel can stay energized through a suspend or an unplug.
+static FORCE_OFF: [Signal<CriticalSectionRawMutex, ()>; NUM_CHANNELS] =
+    [const { Signal::new() }; NUM_CHANNELS];
+
+/// Queue of channel indices whose state just changed, drained by the writer
+/// task to push one IN report each. A multi-slot queue (not a coalescing
+/// `Watch`) so concurrent changes on different channels are never dropped.
+static STATUS: Channel<CriticalSectionRawMutex, u8, STATUS_QUEUE_LEN> = Channel::new();
+
+/// Live per-channel state, read synchronously by GET_REPORT so a host polling
+/// mid-pulse sees the real remaining time rather than a stale snapshot.
+static CHANNEL_STATE: BlockingMutex<CriticalSectionRawMutex, RefCell<[ChannelState; NUM_CHANNELS]>> =
+    BlockingMutex::new(RefCell::new(
+        [ChannelState {
+            on: false,
+            deadline: None,
+        }; NUM_CHANNELS],
+    ));
+
+/// Asks the USB task to wake a suspended host before a queued pulse runs.
+static REMOTE_WAKEUP: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Raised when the bus resumes, so a "wake before pulsing" command can wait for
+/// the host to actually be back before toggling.
+static RESUMED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Carries settings to persist. The blocking flash erase/program runs in a
+/// dedicated task so it never stalls the USB control handler.
+static FLASH_WRITE: Signal<CriticalSectionRawMutex, Settings> = Signal::new();
+
+/// Live state of a single channel, held in [`CHANNEL_STATE`].
+#[derive(Clone, Copy)]
+struct ChannelState {
+    on: bool,
+    deadline: Option<Instant>,
+}
+
+/// Record a channel's live state for GET_REPORT to read back.
+fn set_channel_state(channel: u8, on: bool, deadline: Option<Instant>) {
+    CHANNEL_STATE.lock(|c| c.borrow_mut()[channel as usize] = ChannelState { on, deadline });
+}
+
+/// Build a status snapshot for a channel, computing the remaining time live.
+fn channel_status(channel: u8) -> RelayStatus {
+    let state = CHANNEL_STATE.lock(|c| c.borrow()[channel as usize]);
+    let remaining_ms = match state.deadline {
+        Some(d) => d.saturating_duration_since(Instant::now()).as_millis() as u32,
+        None => 0,
+    };
+    RelayStatus {
+        channel,
+        on: state.on,
+        remaining_ms,
+    }
+}
+
+// ----- Settings persisted to flash
+
+/// Total flash size of the module (Raspberry Pi Pico: 2 MiB).
+const FLASH_SIZE: u32 = 2 * 1024 * 1024;
+/// Flash erase granularity.
+const FLASH_SECTOR_SIZE: u32 = 4096;
+/// Flash program granularity.
+const FLASH_PAGE_SIZE: usize = 256;
+/// Offset of the settings sector: the last sector of flash.
+const SETTINGS_OFFSET: u32 = FLASH_SIZE - FLASH_SECTOR_SIZE;
+/// Base address of the memory-mapped (XIP) flash window.
+const XIP_BASE: u32 = 0x1000_0000;
+/// Marks the settings sector as written by this firmware.
+const SETTINGS_MAGIC: u32 = 0x5057_5348; // "PWSH"
+
+/// User-configurable, flash-backed behavior of the switch.
+#[derive(Clone, Copy)]
+struct Settings {
+    /// Default pulse duration used by the power-on action, in milliseconds.
+    default_duration: u32,
+    /// Whether to pulse the default duration on boot without host interaction.
+    auto_pulse: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            default_duration: 1000, // 1 second
+            auto_pulse: false,
+        }
+    }
+}
+
+impl Settings {
+    /// Read the settings from the last flash sector, falling back to the
+    /// defaults if the sector is blank, corrupt, or holds an out-of-range value.
+    fn load() -> Self {
+        // Flash is memory-mapped, so a plain read from the XIP window works
+        let ptr = (XIP_BASE + SETTINGS_OFFSET) as *const u8;
+        let raw = unsafe { core::slice::from_raw_parts(ptr, 9) };
+
+        if u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) != SETTINGS_MAGIC {
+            return Settings::default();
+        }
+        let default_duration = u32::from_le_bytes([raw[4], raw[5], raw[6], raw[7]]);
+        if (default_duration == 0) | (default_duration > MAX_DURATION) {
+            return Settings::default();
+        }
+        Settings {
+            default_duration,
+            auto_pulse: raw[8] != 0,
+        }
+    }
+
+    /// Erase and rewrite the settings sector. Gated behind an explicit host
+    /// command to avoid wearing out the flash.
+    fn save(&self) {
+        let mut page = [0xFFu8; FLASH_PAGE_SIZE];
+        page[0..4].copy_from_slice(&SETTINGS_MAGIC.to_le_bytes());
+        page[4..8].copy_from_slice(&self.default_duration.to_le_bytes());
+        page[8] = self.auto_pulse as u8;
+
+        // Flash operations must run with interrupts masked and the XIP cache quiet
+        critical_section::with(|_| unsafe {
+            flash_range_erase(SETTINGS_OFFSET, FLASH_SECTOR_SIZE, true);
+            flash_range_program(SETTINGS_OFFSET, &page, true);
+        });
+    }
+
+    /// Serialize into a feature report body: [default_duration (BE), auto_pulse].
+    fn to_report(self) -> [u8; 5] {
+        let d = self.default_duration.to_be_bytes();
+        [d[0], d[1], d[2], d[3], self.auto_pulse as u8]
+    }
+}
+
+/// Persist settings to flash off the USB control-handler path. The erase masks
+/// interrupts for tens of milliseconds and stalls the whole executor while it
+/// runs; doing it here lets `set_report` answer the SET_REPORT control transfer
+/// at once instead of blocking inside the handler for the erase.
+#[embassy_executor::task]
+async fn flash_task() {
+    loop {
+        let settings = FLASH_WRITE.wait().await;
+        settings.save();
+    }
+}
+
+/// A command for a single channel, decoded from the host's command report.
+#[derive(Clone, Copy)]
+enum Command {
+    /// Energize for the given number of milliseconds. A pulse arriving while
+    /// one is already running is discarded (the original behavior).
+    Pulse { duration: u32, wake: bool },
+    /// Restart the timer with a new duration, even mid-pulse (extend).
+    Retrigger { duration: u32, wake: bool },
+    /// Drop the relay immediately and reset the timer.
+    Cancel,
+}
+
+/// One task per relay output: energizes its pin for the requested duration and
+/// reports state changes back to the host.
+#[embassy_executor::task(pool_size = NUM_CHANNELS)]
+async fn toggle_task(channel: u8, mut relay_pin: Output<'static>) {
+    let mut receiver = COMMANDS[channel as usize].receiver().unwrap();
+    let status = STATUS.sender();
+    let force_off = &FORCE_OFF[channel as usize];
+    loop {
+        // Wait for a command that starts a pulse; a cancel with nothing running
+        // has nothing to do.
+        let (duration, wake) = match receiver.changed().await {
+            Command::Pulse { duration, wake } | Command::Retrigger { duration, wake } => {
+                (duration, wake)
+            }
+            Command::Cancel => continue,
+        };
+
+        // "Wake before pulsing": resume a suspended host and wait for it to be
+        // back before driving the relay, so the pulse is never lost on a
+        // still-suspended bus.
+        if wake && SUSPENDED.load(Ordering::Acquire) {
+            info!("Channel {}: waking host before pulse", channel);
+            RESUMED.reset();
+            REMOTE_WAKEUP.signal(());
+            RESUMED.wait().await;
+        }
+
+        info!("Channel {}: wait for {}ms", channel, duration);
+        relay_pin.set_high();
+
+        // Stay high until the timer expires, a cancel/force-off arrives, or a
+        // retrigger restarts the timer. `Timer::at` keeps the same absolute
+        // deadline across iterations, so an ignored pulse does not reset it.
+        let mut deadline = Instant::now() + Duration::from_millis(duration as u64);
+        set_channel_state(channel, true, Some(deadline));
+        status.send(channel).await;
+        force_off.reset(); // Drop any stale request from a previous pulse
+
+        loop {
+            match select3(Timer::at(deadline), receiver.changed(), force_off.wait()).await {
+                Either3::First(_) => {
+                    info!("Channel {}: finished waiting !", channel);
+                    break;
+                }
+                Either3::Second(Command::Cancel) => {
+                    info!("Channel {}: cancelled", channel);
+                    break;
+                }
+                Either3::Second(Command::Retrigger { duration: d, .. }) => {
+                    deadline = Instant::now() + Duration::from_millis(d as u64);
+                    info!("Channel {}: retriggered for {}ms", channel, d);
+                    set_channel_state(channel, true, Some(deadline));
+                    status.send(channel).await;
+                }
+                // A plain pulse during an active pulse is discarded
+                Either3::Second(Command::Pulse { .. }) => {}
+                Either3::Third(_) => {
+                    info!("Channel {}: forced off", channel);
+                    break;
+                }
+            }
+        }
+
+        relay_pin.set_low();
+        set_channel_state(channel, false, None);
+        status.send(channel).await;
+    }
+}
+
 #[embassy_executor::main]
-async fn main(_spawner: Spawner) {
+async fn main(spawner: Spawner) {
     let p = init(Default::default());
 
     info!("Let's go");
 
-    // Pin
-    let mut relay_pin = Output::new(p.PIN_10, Low);
+    // One output per channel; spawn a toggle task driving each
+    let pins = [
+        Output::new(p.PIN_10, Low),
+        Output::new(p.PIN_11, Low),
+        Output::new(p.PIN_12, Low),
+        Output::new(p.PIN_13, Low),
+    ];
+    for (channel, pin) in pins.into_iter().enumerate() {
+        spawner.spawn(toggle_task(channel as u8, pin)).unwrap();
+    }
+
+    // Dedicated task for blocking flash writes, kept off the USB control path
+    spawner.spawn(flash_task()).unwrap();
 
-    // Setup task to toggle pin
-    let watch = Watch::<CriticalSectionRawMutex, u32, 4>::new();
-    let mut receiver = watch.receiver().unwrap();
-    let transmitter = watch.sender();
-    let toggle_task = async {
-        loop {
-            let wait_for = receiver.changed().await;
-            info!("Wait for {}ms", wait_for);
-            relay_pin.set_high();
-            Timer::after_millis(wait_for as u64).await;
-            info!("Finished waiting !");
-            relay_pin.set_low();
-            receiver.try_changed(); // Force values sent while waiting to get discarded
-        }
-    };
+    // Load persisted settings before anything else might read or act on them
+    let settings = Settings::load();
+
+    // The writer task is the only consumer of change notifications; GET_REPORT
+    // reads the live state directly from CHANNEL_STATE.
+    let status_rx = STATUS.receiver();
 
     // Setup USB
     let driver = Driver::new(p.USB, Irqs);
@@ -65,11 +332,12 @@ async fn main(_spawner: Spawner) {
     config.serial_number = Some("The Only One");
     config.max_power = 100;
     config.max_packet_size_0 = 64;
+    config.supports_remote_wakeup = true;
     let mut config_descriptor = [0; 256];
     let mut bos_descriptor = [0; 256];
     let mut msos_descriptor = [0; 256];
     let mut control_buf = [0; 64];
-    let mut request_handler = MyRequestHandler { tx: transmitter };
+    let mut request_handler = MyRequestHandler { settings };
     let mut device_handler = MyDeviceHandler {};
     let mut state = State::new();
     let mut builder = Builder::new(
@@ -87,17 +355,51 @@ async fn main(_spawner: Spawner) {
         poll_ms: 60,
         max_packet_size: 64,
     };
-    let hid = HidReaderWriter::<_, 4, 0>::new(&mut builder, &mut state, config);
+    let hid = HidReaderWriter::<_, 8, 8>::new(&mut builder, &mut state, config);
     let mut usb = builder.build();
-    let usb_fut = usb.run();
-    let (reader, mut _writer) = hid.split();
+    let (reader, mut writer) = hid.split();
+
+    // Run the bus, honoring remote-wakeup requests while the host is suspended
+    let usb_fut = async {
+        loop {
+            usb.run_until_suspend().await;
+            match select(usb.wait_resume(), REMOTE_WAKEUP.wait()).await {
+                Either::First(_) => (),
+                Either::Second(_) => {
+                    if let Err(e) = usb.remote_wakeup().await {
+                        info!("Remote wakeup failed: {:?}", e);
+                    }
+                }
+            }
+        }
+    };
+
+    // Push an IN report to the host whenever a channel's relay changes state
+    let writer_task = async {
+        loop {
+            let channel = status_rx.receive().await;
+            let report = channel_status(channel).to_report();
+            if let Err(e) = writer.write(&report).await {
+                info!("Failed to send status report: {:?}", e);
+            }
+        }
+    };
+
+    // Auto-pulse channel 0 on boot if the stored settings ask for it
+    if settings.auto_pulse {
+        info!("Auto-pulse on boot for {}ms", settings.default_duration);
+        COMMANDS[0].sender().send(Command::Pulse {
+            duration: settings.default_duration,
+            wake: false,
+        });
+    }
 
     info!("Now waiting");
 
     // Wait for anything coming in
     join(
         usb_fut,
-        join(reader.run(false, &mut request_handler), toggle_task),
+        join(reader.run(true, &mut request_handler), writer_task),
     )
     .await;
 }
@@ -111,45 +413,221 @@ const HID_DESCRIPTOR: &[u8] = &[
     0xA1, 1,    // Collection: Application
     0x15, 0,    // Logical Minimum: 0
     0x27, 0xFF, 0xFF, 0xFF, 0xFF, // Logical Maximum: 0xFFFFFFFF
-    0x85, 1,    // Report ID: 1
+    // Report ID 1: command (channel index + duration)
+    0x85, 1,    // Report ID: 1 (command)
+    0x75, 8,    // Report Size: 8 bits
+    0x95, 1,    // Report Count: 1
+    0x9, 0,     // Usage: Undefined (channel index)
+    0x91, 0x82, // Output: Variable, Volatile
     0x75, 0x20, // Report Size: 32 bits
     0x95, 1,    // Report Count: 1
-    0x9, 0,     // Usage: Undefined
+    0x9, 0,     // Usage: Undefined (duration, milliseconds)
+    0x91, 0x82, // Output: Variable, Volatile
+    // Report IDs 2..=5: one status report per channel (channel, state, remaining)
+    0x85, 2,    // Report ID: 2 (channel 0 status)
+    0x75, 8,    // Report Size: 8 bits
+    0x95, 1,    // Report Count: 1
+    0x9, 0,     // Usage: Undefined (channel index)
+    0x81, 0x82, // Input: Variable, Volatile
+    0x75, 8,    // Report Size: 8 bits
+    0x95, 1,    // Report Count: 1
+    0x9, 0,     // Usage: Undefined (relay state)
     0x81, 0x82, // Input: Variable, Volatile
+    0x75, 0x20, // Report Size: 32 bits
+    0x95, 1,    // Report Count: 1
+    0x9, 0,     // Usage: Undefined (remaining milliseconds)
+    0x81, 0x82, // Input: Variable, Volatile
+    0x85, 3,    // Report ID: 3 (channel 1 status)
+    0x75, 8,  0x95, 1,  0x9, 0,  0x81, 0x82, // channel index
+    0x75, 8,  0x95, 1,  0x9, 0,  0x81, 0x82, // relay state
+    0x75, 0x20, 0x95, 1, 0x9, 0, 0x81, 0x82, // remaining milliseconds
+    0x85, 4,    // Report ID: 4 (channel 2 status)
+    0x75, 8,  0x95, 1,  0x9, 0,  0x81, 0x82, // channel index
+    0x75, 8,  0x95, 1,  0x9, 0,  0x81, 0x82, // relay state
+    0x75, 0x20, 0x95, 1, 0x9, 0, 0x81, 0x82, // remaining milliseconds
+    0x85, 5,    // Report ID: 5 (channel 3 status)
+    0x75, 8,  0x95, 1,  0x9, 0,  0x81, 0x82, // channel index
+    0x75, 8,  0x95, 1,  0x9, 0,  0x81, 0x82, // relay state
+    0x75, 0x20, 0x95, 1, 0x9, 0, 0x81, 0x82, // remaining milliseconds
+    // Report ID 6: settings (default duration + power-on action)
+    0x85, 6,    // Report ID: 6 (settings)
+    0x75, 0x20, // Report Size: 32 bits
+    0x95, 1,    // Report Count: 1
+    0x9, 0,     // Usage: Undefined (default pulse duration, milliseconds)
+    0xB1, 0x82, // Feature: Variable, Volatile
+    0x75, 8,    // Report Size: 8 bits
+    0x95, 1,    // Report Count: 1
+    0x9, 0,     // Usage: Undefined (power-on action)
+    0xB1, 0x82, // Feature: Variable, Volatile
     0xC0        // End Collection
 ];
 
+// ----- Relay Status
+
+/// Snapshot of one channel's relay, published to the host through that
+/// channel's status report (Report IDs 2..=5).
+#[derive(Clone, Copy)]
+struct RelayStatus {
+    channel: u8,
+    on: bool,
+    remaining_ms: u32,
+}
+
+impl RelayStatus {
+    /// Serialize into an IN report: [Report ID, channel, state, remaining_ms (BE)].
+    /// Each channel has its own Report ID (`STATUS_REPORT_BASE + channel`).
+    fn to_report(self) -> [u8; 7] {
+        let ms = self.remaining_ms.to_be_bytes();
+        let id = STATUS_REPORT_BASE + self.channel;
+        [id, self.channel, self.on as u8, ms[0], ms[1], ms[2], ms[3]]
+    }
+}
+
 // ----- Request Handler
 
-struct MyRequestHandler<'a> {
-    tx: Sender<'a, CriticalSectionRawMutex, u32, 4>,
+struct MyRequestHandler {
+    settings: Settings,
 }
 
-impl RequestHandler for MyRequestHandler<'_> {
+impl RequestHandler for MyRequestHandler {
+    // This is where the host reads a channel's state (per-channel IN report ID)
+    // and the settings (feature report).
+    fn get_report(&mut self, id: ReportId, buf: &mut [u8]) -> Option<usize> {
+        match id {
+            ReportId::In(n) if (STATUS_REPORT_BASE..SETTINGS_REPORT_ID).contains(&n) => {
+                let channel = n - STATUS_REPORT_BASE;
+                let report = channel_status(channel).to_report();
+                copy_body(&report[1..], buf) // Control transfer carries the ID
+            }
+            ReportId::Feature(n) if n == SETTINGS_REPORT_ID => {
+                copy_body(&self.settings.to_report(), buf)
+            }
+            _ => None,
+        }
+    }
+
     // This is where data sent from computer will end up
     fn set_report(&mut self, id: ReportId, data: &[u8]) -> OutResponse {
         info!("Report {}, Received {:?}", id, data);
 
-        // Make sure this is a 4-byte value
-        let duration_bytes = match data.try_into() {
+        // The settings feature report is handled separately
+        if let ReportId::Feature(n) = id {
+            if n == SETTINGS_REPORT_ID {
+                return self.write_settings(data);
+            }
+            return OutResponse::Rejected;
+        }
+
+        // Everything else must be the OUT command report. `reader.run(true, ..)`
+        // strips the leading report-ID byte, so `data` is just the body here,
+        // matching the control SET_REPORT path.
+        match id {
+            ReportId::Out(n) if n == COMMAND_REPORT_ID => {}
+            _ => return OutResponse::Rejected,
+        }
+
+        // A command is a channel index followed by a big-endian u32 duration
+        let [channel, d0, d1, d2, d3] = match data.try_into() {
             Ok(v) => v,
             Err(_) => return OutResponse::Rejected,
         };
 
-        // Convert to a u32
-        let duration = u32::from_be_bytes(duration_bytes);
+        // Reject commands aimed at a channel this board does not have
+        if channel as usize >= NUM_CHANNELS {
+            return OutResponse::Rejected;
+        }
+
+        // The top two bits of the duration are flags (retrigger, wake); the
+        // remaining bits carry the duration, and a duration of 0 means cancel.
+        let raw = u32::from_be_bytes([d0, d1, d2, d3]);
+        let retrigger = raw & RETRIGGER_FLAG != 0;
+        let wake = raw & WAKE_FLAG != 0;
+        let duration = raw & !(RETRIGGER_FLAG | WAKE_FLAG);
+
+        let command = if duration == 0 {
+            Command::Cancel
+        } else if duration <= MAX_DURATION {
+            if retrigger {
+                Command::Retrigger { duration, wake }
+            } else {
+                Command::Pulse { duration, wake }
+            }
+        } else {
+            // Out of range: ignore it, as the original did
+            return OutResponse::Accepted;
+        };
+
+        COMMANDS[channel as usize].sender().send(command);
+
+        OutResponse::Accepted
+    }
+}
+
+impl MyRequestHandler {
+    /// Validate and persist a settings feature report.
+    fn write_settings(&mut self, data: &[u8]) -> OutResponse {
+        let [d0, d1, d2, d3, auto_pulse] = match data.try_into() {
+            Ok(v) => v,
+            Err(_) => return OutResponse::Rejected,
+        };
 
-        // Only process values in range
-        if (duration > 0) & (duration <= MAX_DURATION) {
-            self.tx.send(duration);
+        let default_duration = u32::from_be_bytes([d0, d1, d2, d3]);
+        if (default_duration == 0) | (default_duration > MAX_DURATION) {
+            return OutResponse::Rejected;
         }
 
+        self.settings = Settings {
+            default_duration,
+            auto_pulse: auto_pulse != 0,
+        };
+        // Hand the write to the flash task so this control transfer returns now
+        FLASH_WRITE.signal(self.settings);
+
         OutResponse::Accepted
     }
 }
 
+/// Copy a report body into the control buffer, returning its length.
+fn copy_body(body: &[u8], buf: &mut [u8]) -> Option<usize> {
+    if buf.len() < body.len() {
+        return None;
+    }
+    buf[..body.len()].copy_from_slice(body);
+    Some(body.len())
+}
+
 // ----- Device Handler
 
 struct MyDeviceHandler {}
 
-impl Handler for MyDeviceHandler {}
+impl Handler for MyDeviceHandler {
+    fn enabled(&mut self, enabled: bool) {
+        if !enabled {
+            // Bus powered down / cable pulled: never leave a relay latched
+            SUSPENDED.store(false, Ordering::Release);
+            force_off_all();
+        }
+    }
+
+    fn reset(&mut self) {
+        // A bus reset drops us out of the Configured state
+        force_off_all();
+    }
+
+    fn suspended(&mut self, suspended: bool) {
+        SUSPENDED.store(suspended, Ordering::Release);
+        if suspended {
+            force_off_all();
+        } else {
+            // Bus is back: release any command waiting to pulse on resume
+            RESUMED.signal(());
+        }
+    }
+}
+
+/// Ask every channel to drop its relay immediately.
+fn force_off_all() {
+    for force_off in &FORCE_OFF {
+        force_off.signal(());
+    }
+}